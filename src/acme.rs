@@ -0,0 +1,450 @@
+//! Minimal embedded ACME (RFC 8555) client.
+//!
+//! Just enough of the protocol to get `emberkeyd` a certificate from Let's
+//! Encrypt without shelling out to `certbot` or similar: account creation,
+//! `http-01` validation, order finalization via a CSR, and a background loop
+//! that renews the certificate once it is within ~30 days of expiry.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use color_eyre::eyre::{eyre, Result};
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand::thread_rng;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use warp::Filter;
+
+const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+const RENEW_WITHIN: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: u32 = 30;
+
+/// Shared table of in-flight `http-01` tokens -> key authorizations, read by
+/// the `GET /.well-known/acme-challenge/{token}` route and written by the
+/// ACME client while an order is being validated.
+pub type ChallengeStore = Mutex<HashMap<String, String>>;
+
+#[derive(Debug, Clone)]
+pub struct AcmeConfig {
+    pub domain: String,
+    pub contact_email: String,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub directory_url: String,
+}
+
+impl AcmeConfig {
+    pub fn from_env(domain: String, contact_email: String) -> AcmeConfig {
+        let directory_url = std::env::var("EMBERKEYD_ACME_DIRECTORY")
+            .unwrap_or_else(|_| LETS_ENCRYPT_DIRECTORY.to_string());
+        AcmeConfig {
+            domain,
+            contact_email,
+            cert_path: PathBuf::from("tls/cert.pem"),
+            key_path: PathBuf::from("tls/key.pem"),
+            directory_url,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Directory {
+    #[serde(rename = "newNonce")]
+    new_nonce: String,
+    #[serde(rename = "newAccount")]
+    new_account: String,
+    #[serde(rename = "newOrder")]
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OrderResponse {
+    status: String,
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorizationResponse {
+    status: String,
+    challenges: Vec<AcmeChallenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcmeChallenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+}
+
+/// An ACME account key plus the account URL returned by `newAccount`, used to
+/// sign every subsequent request in the order lifecycle.
+struct Account {
+    key: SigningKey,
+    kid: String,
+}
+
+impl Account {
+    fn jwk(&self) -> Value {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+            "y": URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        })
+    }
+
+    /// RFC 7638 JWK thumbprint, used to build the `http-01` key authorization.
+    fn thumbprint(&self) -> String {
+        let jwk = self.jwk();
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap(),
+            jwk["y"].as_str().unwrap()
+        );
+        URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+    }
+
+    fn sign(&self, protected: &Value, payload: Option<&Value>) -> Value {
+        let protected_b64 = URL_SAFE_NO_PAD.encode(protected.to_string());
+        let payload_b64 = match payload {
+            Some(p) => URL_SAFE_NO_PAD.encode(p.to_string()),
+            None => String::new(),
+        };
+        let signing_input = format!("{protected_b64}.{payload_b64}");
+        let signature: Signature = self.key.sign(signing_input.as_bytes());
+        json!({
+            "protected": protected_b64,
+            "payload": payload_b64,
+            "signature": URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+        })
+    }
+}
+
+use sha2::{Digest, Sha256};
+
+struct AcmeClient {
+    http: reqwest::Client,
+    directory: Directory,
+}
+
+impl AcmeClient {
+    async fn new(directory_url: &str) -> Result<AcmeClient> {
+        let http = reqwest::Client::new();
+        let directory: Directory = http.get(directory_url).send().await?.json().await?;
+        Ok(AcmeClient { http, directory })
+    }
+
+    async fn nonce(&self) -> Result<String> {
+        let resp = self.http.head(&self.directory.new_nonce).send().await?;
+        resp.headers()
+            .get("replay-nonce")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string())
+            .ok_or_else(|| eyre!("ACME server did not return a replay-nonce"))
+    }
+
+    /// POST a JWS-signed request, keyed either by the account's `kid` (once
+    /// registered) or by its raw `jwk` (only valid for `newAccount`).
+    async fn post_signed(
+        &self,
+        url: &str,
+        payload: Option<&Value>,
+        key: &SigningKey,
+        kid_or_jwk: Value,
+    ) -> Result<reqwest::Response> {
+        let nonce = self.nonce().await?;
+        let mut protected = json!({
+            "alg": "ES256",
+            "nonce": nonce,
+            "url": url,
+        });
+        for (k, v) in kid_or_jwk.as_object().unwrap() {
+            protected[k] = v.clone();
+        }
+        let account = Account {
+            key: key.clone(),
+            kid: String::new(),
+        };
+        let body = account.sign(&protected, payload);
+        let resp = self
+            .http
+            .post(url)
+            .header("content-type", "application/jose+json")
+            .json(&body)
+            .send()
+            .await?;
+        Ok(resp)
+    }
+
+    async fn new_account(&self, key: &SigningKey, contact_email: &str) -> Result<Account> {
+        let account_key = Account {
+            key: key.clone(),
+            kid: String::new(),
+        };
+        let payload = json!({
+            "termsOfServiceAgreed": true,
+            "contact": [format!("mailto:{contact_email}")],
+        });
+        let resp = self
+            .post_signed(
+                &self.directory.new_account,
+                Some(&payload),
+                key,
+                json!({"jwk": account_key.jwk()}),
+            )
+            .await?;
+        if !resp.status().is_success() {
+            return Err(eyre!("newAccount failed: {}", resp.text().await?));
+        }
+        let kid = resp
+            .headers()
+            .get("location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| eyre!("newAccount response missing Location header"))?
+            .to_string();
+        Ok(Account {
+            key: key.clone(),
+            kid,
+        })
+    }
+
+    async fn post_as_account(
+        &self,
+        account: &Account,
+        url: &str,
+        payload: Option<&Value>,
+    ) -> Result<reqwest::Response> {
+        let nonce = self.nonce().await?;
+        let protected = json!({
+            "alg": "ES256",
+            "nonce": nonce,
+            "url": url,
+            "kid": account.kid,
+        });
+        let body = account.sign(&protected, payload);
+        let resp = self
+            .http
+            .post(url)
+            .header("content-type", "application/jose+json")
+            .json(&body)
+            .send()
+            .await?;
+        Ok(resp)
+    }
+
+    async fn new_order(&self, account: &Account, domain: &str) -> Result<(String, OrderResponse)> {
+        let payload = json!({
+            "identifiers": [{"type": "dns", "value": domain}],
+        });
+        let resp = self
+            .post_as_account(account, &self.directory.new_order, Some(&payload))
+            .await?;
+        if !resp.status().is_success() {
+            return Err(eyre!("newOrder failed: {}", resp.text().await?));
+        }
+        let order_url = resp
+            .headers()
+            .get("location")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| eyre!("newOrder response missing Location header"))?
+            .to_string();
+        let order: OrderResponse = resp.json().await?;
+        Ok((order_url, order))
+    }
+
+    async fn fetch_order(&self, account: &Account, order_url: &str) -> Result<OrderResponse> {
+        let resp = self.post_as_account(account, order_url, None).await?;
+        Ok(resp.json().await?)
+    }
+
+    async fn fetch_authorization(
+        &self,
+        account: &Account,
+        authz_url: &str,
+    ) -> Result<AuthorizationResponse> {
+        let resp = self.post_as_account(account, authz_url, None).await?;
+        Ok(resp.json().await?)
+    }
+}
+
+/// Serve the `http-01` challenge response for any token the ACME client is
+/// currently waiting on validation for.
+pub fn challenge_route(
+    store: &'static ChallengeStore,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path!(".well-known" / "acme-challenge" / String))
+        .and_then(move |token: String| async move {
+            match store.lock().unwrap().get(&token) {
+                Some(key_authorization) => Ok(key_authorization.clone()),
+                None => Err(warp::reject::not_found()),
+            }
+        })
+}
+
+/// Run the full order lifecycle once and write the resulting certificate
+/// chain and private key to `config.cert_path` / `config.key_path`.
+///
+/// `http-01` is validated by the CA over plain HTTP on port 80, which is
+/// otherwise unused by this daemon (the public listener serves HTTPS on
+/// 443), so we bring up a short-lived plaintext listener for `challenge_route`
+/// around the order and tear it down once validation is done.
+pub async fn obtain_certificate(config: &AcmeConfig, store: &'static ChallengeStore) -> Result<()> {
+    let challenge_listener = tokio::spawn(warp::serve(challenge_route(store)).run(([0, 0, 0, 0], 80)));
+    let result = run_order(config, store).await;
+    challenge_listener.abort();
+    result
+}
+
+async fn run_order(config: &AcmeConfig, store: &'static ChallengeStore) -> Result<()> {
+    info!("Requesting certificate for {} via ACME", config.domain);
+    let client = AcmeClient::new(&config.directory_url).await?;
+    let account_key = SigningKey::random(&mut thread_rng());
+    let account = client
+        .new_account(&account_key, &config.contact_email)
+        .await?;
+    let thumbprint = account.thumbprint();
+
+    let (order_url, mut order) = client.new_order(&account, &config.domain).await?;
+
+    for authz_url in &order.authorizations {
+        let authz = client.fetch_authorization(&account, authz_url).await?;
+        if authz.status == "valid" {
+            continue;
+        }
+        let http01 = authz
+            .challenges
+            .iter()
+            .find(|c| c.kind == "http-01")
+            .ok_or_else(|| eyre!("server offered no http-01 challenge for {authz_url}"))?;
+
+        let key_authorization = format!("{}.{}", http01.token, thumbprint);
+        store
+            .lock()
+            .unwrap()
+            .insert(http01.token.clone(), key_authorization);
+
+        client
+            .post_as_account(&account, &http01.url, Some(&json!({})))
+            .await?;
+
+        poll_until(|| {
+            let account = &account;
+            let client = &client;
+            async move {
+                let authz = client.fetch_authorization(account, authz_url).await?;
+                Ok(authz.status == "valid")
+            }
+        })
+        .await?;
+    }
+
+    let cert_key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+    let mut params = rcgen::CertificateParams::new(vec![config.domain.clone()]);
+    params.key_pair = Some(cert_key_pair);
+    let cert = rcgen::Certificate::from_params(params)?;
+    let csr_der = cert.serialize_request_der()?;
+
+    let resp = client
+        .post_as_account(
+            &account,
+            &order.finalize,
+            Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr_der) })),
+        )
+        .await?;
+    if !resp.status().is_success() {
+        return Err(eyre!("finalize failed: {}", resp.text().await?));
+    }
+
+    poll_until(|| {
+        let account = &account;
+        let client = &client;
+        let order_url = &order_url;
+        async move {
+            order = client.fetch_order(account, order_url).await?;
+            Ok(order.certificate.is_some())
+        }
+    })
+    .await?;
+
+    let cert_url = order
+        .certificate
+        .ok_or_else(|| eyre!("order finalized without a certificate URL"))?;
+    let chain_pem = client
+        .post_as_account(&account, &cert_url, None)
+        .await?
+        .text()
+        .await?;
+
+    if let Some(parent) = config.cert_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&config.cert_path, chain_pem)?;
+    std::fs::write(&config.key_path, cert.serialize_private_key_pem())?;
+    info!("Obtained certificate for {}", config.domain);
+    Ok(())
+}
+
+async fn poll_until<F, Fut>(mut check: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<bool>>,
+{
+    for _ in 0..POLL_ATTEMPTS {
+        if check().await? {
+            return Ok(());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Err(eyre!("timed out waiting for ACME validation"))
+}
+
+fn certificate_expires_within(cert_path: &std::path::Path, window: Duration) -> bool {
+    let pem = match std::fs::read(cert_path) {
+        Ok(pem) => pem,
+        Err(_) => return true,
+    };
+    let Some(der) = pem_first_certificate_der(&pem) else {
+        return true;
+    };
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(&der) else {
+        return true;
+    };
+    match cert.validity().time_to_expiration() {
+        Some(remaining) => remaining.whole_seconds() <= window.as_secs() as i64,
+        None => true,
+    }
+}
+
+fn pem_first_certificate_der(pem: &[u8]) -> Option<Vec<u8>> {
+    pem::parse(pem).ok().map(|p| p.contents)
+}
+
+/// Spawn the background renewal loop: once a day, check whether the current
+/// certificate is within [`RENEW_WITHIN`] of expiry and, if so, obtain a
+/// fresh one.
+pub fn spawn_renewal_task(config: AcmeConfig, store: &'static ChallengeStore) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(24 * 60 * 60)).await;
+            if certificate_expires_within(&config.cert_path, RENEW_WITHIN) {
+                if let Err(e) = obtain_certificate(&config, store).await {
+                    error!("Certificate renewal failed: {e}");
+                } else {
+                    info!("Renewed certificate for {}", config.domain);
+                }
+            } else {
+                warn!("Certificate for {} not yet due for renewal", config.domain);
+            }
+        }
+    });
+}
@@ -0,0 +1,47 @@
+//! Shared cryptographic primitives: AES-256-GCM encrypt/decrypt wrappers and
+//! the x25519 ECDH + HKDF key derivation backing the encrypted request
+//! envelope. `Challenge`/`Response` proof-of-possession and the envelope
+//! layer both build on the same two AES helpers so there is exactly one
+//! place that decides nonce generation and AEAD failure handling.
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use hkdf::Hkdf;
+use rand::thread_rng;
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub type AesKey = Key<Aes256Gcm>;
+pub type AesNonce = Nonce<Aes256Gcm>;
+
+const HKDF_SALT: &[u8] = b"emberkeyd-envelope-v1";
+
+/// Derive the AES-256-GCM key shared with the holder of `their_pubkey`, given
+/// our x25519 secret, via `HKDF-SHA256(DH(our_secret, their_pubkey))`.
+pub fn get_x25519_symmetric_key(our_secret: &StaticSecret, their_pubkey: &PublicKey) -> AesKey {
+    let shared_secret = our_secret.diffie_hellman(their_pubkey);
+    let hkdf = Hkdf::<Sha256>::new(Some(HKDF_SALT), shared_secret.as_bytes());
+    let mut key_bytes = [0u8; 32];
+    hkdf.expand(b"emberkeyd-aes-gcm-key", &mut key_bytes)
+        .expect("32 is a valid HKDF-SHA256 output length");
+    *AesKey::from_slice(&key_bytes)
+}
+
+/// Encrypt `plaintext` under `key` with a freshly generated nonce.
+pub fn encrypt_aes_gcm(key: &AesKey, plaintext: &[u8]) -> (AesNonce, Vec<u8>) {
+    let cipher = Aes256Gcm::new(key);
+    let nonce = Aes256Gcm::generate_nonce(thread_rng());
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .expect("AES-GCM encryption does not fail for in-memory buffers");
+    (nonce, ciphertext)
+}
+
+/// Decrypt `ciphertext` under `key` and `nonce`, returning `None` on
+/// authentication failure.
+pub fn decrypt_aes_gcm(key: &AesKey, nonce: &AesNonce, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    let cipher = Aes256Gcm::new(key);
+    cipher.decrypt(nonce, ciphertext).ok()
+}
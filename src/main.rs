@@ -1,24 +1,74 @@
-use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit},
-    Aes256Gcm, Key,
-};
+mod acme;
+mod crypto;
+
+use aes_gcm::{aead::KeyInit, Aes256Gcm};
 use asym_ratchet::PublicKey;
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
+use crypto::{AesKey, AesNonce};
 use rand::{thread_rng, Rng};
-use rusqlite::{params, Connection, ErrorCode};
+use rusqlite::{params, params_from_iter, Connection, ErrorCode};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::sync::Mutex;
 use tracing::{error, info};
 use warp::{http::StatusCode, Filter};
+use x25519_dalek::{PublicKey as X25519PublicKey, StaticSecret};
+
+/// A machine-readable error code alongside a human-readable message, so
+/// clients can branch on `code` (`"name_taken"`, `"not_found"`, ...) without
+/// string-matching `message`.
+#[derive(Debug, Clone, Serialize)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+/// The single response shape every route serializes through: both `result`
+/// and `error` are always emitted, regardless of status code, with `error`
+/// null on success and `result` null on failure, so clients don't need a
+/// different parser per route.
+#[derive(Debug, Clone, Serialize)]
+struct ApiResponse<T> {
+    result: Option<T>,
+    error: Option<ApiError>,
+}
+
+impl<T> ApiResponse<T> {
+    fn ok(result: T) -> ApiResponse<T> {
+        ApiResponse {
+            result: Some(result),
+            error: None,
+        }
+    }
 
-type AesKey = Key<Aes256Gcm>;
-type AesNonce = aes_gcm::aead::Nonce<Aes256Gcm>;
+    fn err(code: &str, message: impl Into<String>) -> ApiResponse<T> {
+        ApiResponse {
+            result: None,
+            error: Some(ApiError {
+                code: code.to_string(),
+                message: message.into(),
+            }),
+        }
+    }
+}
+
+/// What a successful `Response` authorizes. Bound into the encrypted `State`
+/// at challenge time so a challenge minted for one operation (e.g. rotating
+/// `"alice"`) can't be replayed as proof for another (e.g. registering a
+/// fresh name).
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+enum Operation {
+    Register,
+    Rotate { name: String },
+    Revoke { name: String },
+}
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 struct State {
     challenge_nonce: Vec<u8>,
     pubkey: PublicKey,
+    operation: Operation,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
@@ -26,6 +76,26 @@ struct Request {
     pubkey: PublicKey,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct RotateRequest {
+    name: String,
+    new_pubkey: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct RevokeRequest {
+    name: String,
+}
+
+/// Upper bound on how many names a single `POST /keys` call may request, so
+/// one client can't force an arbitrarily large `IN (...)` query.
+const MAX_BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+struct BatchKeyRequest {
+    names: Vec<String>,
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
 struct Challenge {
     challenge: Vec<u8>,
@@ -42,19 +112,28 @@ struct Response {
 }
 
 impl Challenge {
-    fn new_challenge(my_key: &AesKey, pubkey: &PublicKey) -> Challenge {
+    /// Mint a challenge that, once answered, proves possession of the
+    /// private key behind `encrypt_to` and authorizes `operation`. `pubkey`
+    /// is the key carried along in the encrypted state for the caller to
+    /// persist on success (the new key being registered or rotated in; for
+    /// revocation it is unused).
+    fn new_challenge(
+        my_key: &AesKey,
+        encrypt_to: &PublicKey,
+        pubkey: &PublicKey,
+        operation: Operation,
+    ) -> Challenge {
         let challenge_nonce: [u8; 32] = thread_rng().gen();
-        let cipher = Aes256Gcm::new(my_key);
-        let nonce = Aes256Gcm::generate_nonce(thread_rng());
         let state = State {
             challenge_nonce: challenge_nonce.to_vec(),
             pubkey: pubkey.clone(),
+            operation,
         };
-        let state = bincode::serialize(&state).unwrap();
-        let state = cipher.encrypt(&nonce, state.as_ref()).unwrap();
+        let state_bytes = bincode::serialize(&state).unwrap();
+        let (nonce, state) = crypto::encrypt_aes_gcm(my_key, &state_bytes);
         Challenge {
             challenge: bincode::serialize(
-                &pubkey
+                &encrypt_to
                     .encrypt(thread_rng(), challenge_nonce.to_vec())
                     .unwrap(),
             )
@@ -66,19 +145,307 @@ impl Challenge {
 }
 
 impl Response {
-    fn verify(&self, my_key: &AesKey) -> Option<PublicKey> {
-        let cipher = Aes256Gcm::new(my_key);
+    fn verify(&self, my_key: &AesKey) -> Option<State> {
         let nonce: &AesNonce = self.nonce.as_slice().try_into().ok()?;
-        let plaintext = cipher.decrypt(&nonce, self.state.as_slice()).ok()?;
+        let plaintext = crypto::decrypt_aes_gcm(my_key, nonce, &self.state)?;
         let state: State = bincode::deserialize(&plaintext).ok()?;
         if self.response == state.challenge_nonce {
-            Some(state.pubkey)
+            Some(state)
         } else {
             None
         }
     }
 }
 
+/// The decrypted payload of a `POST /encrypted` envelope: one of the existing
+/// plaintext request types, dispatched through the same handler logic as its
+/// unencrypted route.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum InnerRequest {
+    Challenge(Request),
+    Response(Response),
+    Keys(BatchKeyRequest),
+}
+
+#[derive(Debug, Deserialize)]
+struct EncryptedRequest {
+    ephemeral_pubkey: [u8; 32],
+    iv: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Serialize)]
+struct EncryptedReply {
+    iv: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+fn handle_challenge(my_key: &AesKey, request: Request) -> Challenge {
+    Challenge::new_challenge(
+        my_key,
+        &request.pubkey,
+        &request.pubkey,
+        Operation::Register,
+    )
+}
+
+/// Look up the pubkey currently registered for `name`, deserialized back
+/// into a `PublicKey` so it can be used as a challenge's `encrypt_to` target.
+fn fetch_pubkey(db: &Mutex<Connection>, name: &str) -> Option<PublicKey> {
+    let bytes: Vec<u8> = db
+        .lock()
+        .unwrap()
+        .query_row(
+            "SELECT pubkey FROM keys WHERE name = ?1 AND revoked = 0",
+            params![name],
+            |row| row.get(0),
+        )
+        .ok()?;
+    bincode::deserialize(&bytes).ok()
+}
+
+fn handle_rotate(
+    db: &Mutex<Connection>,
+    my_key: &AesKey,
+    request: RotateRequest,
+) -> (StatusCode, ApiResponse<Value>) {
+    match fetch_pubkey(db, &request.name) {
+        Some(current_pubkey) => (
+            StatusCode::OK,
+            ApiResponse::ok(json!(Challenge::new_challenge(
+                my_key,
+                &current_pubkey,
+                &request.new_pubkey,
+                Operation::Rotate { name: request.name },
+            ))),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            ApiResponse::err("not_found", "no key registered for that name"),
+        ),
+    }
+}
+
+fn handle_revoke(
+    db: &Mutex<Connection>,
+    my_key: &AesKey,
+    request: RevokeRequest,
+) -> (StatusCode, ApiResponse<Value>) {
+    match fetch_pubkey(db, &request.name) {
+        Some(current_pubkey) => (
+            StatusCode::OK,
+            ApiResponse::ok(json!(Challenge::new_challenge(
+                my_key,
+                &current_pubkey,
+                &current_pubkey,
+                Operation::Revoke { name: request.name },
+            ))),
+        ),
+        None => (
+            StatusCode::NOT_FOUND,
+            ApiResponse::err("not_found", "no key registered for that name"),
+        ),
+    }
+}
+
+fn handle_response(
+    db: &Mutex<Connection>,
+    my_key: &AesKey,
+    response: &Response,
+) -> (StatusCode, ApiResponse<Value>) {
+    let state = match response.verify(my_key) {
+        Some(state) => state,
+        None => {
+            return (
+                StatusCode::BAD_REQUEST,
+                ApiResponse::err("failed_challenge", "challenge response did not verify"),
+            )
+        }
+    };
+    match state.operation {
+        Operation::Register => {
+            let keybytes = bincode::serialize(&state.pubkey).unwrap();
+            let conn = db.lock().unwrap();
+            let res = conn.execute(
+                "INSERT INTO keys (name, pubkey) VALUES (?1, ?2);",
+                params![response.name, keybytes],
+            );
+            match res {
+                Ok(_) => {
+                    info!("Inserted key for {}", response.name);
+                    (StatusCode::CREATED, ApiResponse::ok(json!({"generation": 1})))
+                }
+                Err(e) if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) => {
+                    // The name is taken. If it belongs to a tombstoned
+                    // (revoked) row, registering again revives it under the
+                    // new key instead of burning the name forever; the
+                    // generation counter keeps climbing rather than resetting
+                    // to 1, so clients can still tell a revocation happened.
+                    let revived: rusqlite::Result<i64> = conn.query_row(
+                        "UPDATE keys SET pubkey = ?1, generation = generation + 1, revoked = 0
+                         WHERE name = ?2 AND revoked = 1
+                         RETURNING generation",
+                        params![keybytes, response.name],
+                        |row| row.get(0),
+                    );
+                    match revived {
+                        Ok(generation) => {
+                            info!("Re-registered revoked name {}", response.name);
+                            (StatusCode::CREATED, ApiResponse::ok(json!({"generation": generation})))
+                        }
+                        Err(rusqlite::Error::QueryReturnedNoRows) => (
+                            StatusCode::CONFLICT,
+                            ApiResponse::err("name_taken", "name is already registered"),
+                        ),
+                        Err(e) => {
+                            error!("Error reviving revoked name {}: {}", response.name, e);
+                            (
+                                StatusCode::INTERNAL_SERVER_ERROR,
+                                ApiResponse::err("storage_error", "could not insert key"),
+                            )
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Error inserting key for {}: {}", response.name, e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ApiResponse::err("storage_error", "could not insert key"),
+                    )
+                }
+            }
+        }
+        Operation::Rotate { name } => {
+            if name != response.name {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    ApiResponse::err("name_mismatch", "response name does not match the challenge"),
+                );
+            }
+            let keybytes = bincode::serialize(&state.pubkey).unwrap();
+            let generation: rusqlite::Result<i64> = db.lock().unwrap().query_row(
+                "UPDATE keys SET pubkey = ?1, generation = generation + 1 WHERE name = ?2 RETURNING generation",
+                params![keybytes, name],
+                |row| row.get(0),
+            );
+            match generation {
+                Ok(generation) => {
+                    info!("Rotated key for {}", name);
+                    (StatusCode::OK, ApiResponse::ok(json!({"generation": generation})))
+                }
+                Err(e) => {
+                    error!("Error rotating key for {}: {}", name, e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ApiResponse::err("storage_error", "could not rotate key"),
+                    )
+                }
+            }
+        }
+        Operation::Revoke { name } => {
+            if name != response.name {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    ApiResponse::err("name_mismatch", "response name does not match the challenge"),
+                );
+            }
+            // Tombstone rather than delete: the row (and its generation
+            // counter) must survive revocation, otherwise a later
+            // re-registration under the same name would reset generation
+            // back to 1 and break the monotonicity guarantee.
+            let res = db.lock().unwrap().execute(
+                "UPDATE keys SET pubkey = NULL, revoked = 1 WHERE name = ?1",
+                params![name],
+            );
+            match res {
+                Ok(_) => {
+                    info!("Revoked key for {}", name);
+                    (StatusCode::OK, ApiResponse::ok(Value::Null))
+                }
+                Err(e) => {
+                    error!("Error revoking key for {}: {}", name, e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        ApiResponse::err("storage_error", "could not revoke key"),
+                    )
+                }
+            }
+        }
+    }
+}
+
+fn handle_batch_keys(db: &Mutex<Connection>, request: BatchKeyRequest) -> (StatusCode, ApiResponse<Value>) {
+    let mut names = request.names;
+    names.sort_unstable();
+    names.dedup();
+
+    if names.len() > MAX_BATCH_SIZE {
+        return (
+            StatusCode::BAD_REQUEST,
+            ApiResponse::err(
+                "batch_too_large",
+                format!("batch size exceeds limit of {MAX_BATCH_SIZE}"),
+            ),
+        );
+    }
+
+    let mut found: HashMap<String, (Vec<u8>, i64)> = HashMap::new();
+    if !names.is_empty() {
+        let placeholders = names.iter().map(|_| "?").collect::<Vec<_>>().join(", ");
+        let query = format!(
+            "SELECT name, pubkey, generation FROM keys WHERE revoked = 0 AND name IN ({placeholders})"
+        );
+        let conn = db.lock().unwrap();
+        let result: rusqlite::Result<()> = (|| {
+            let mut stmt = conn.prepare(&query)?;
+            let mut rows = stmt.query(params_from_iter(names.iter()))?;
+            while let Some(row) = rows.next()? {
+                found.insert(row.get(0)?, (row.get(1)?, row.get(2)?));
+            }
+            Ok(())
+        })();
+        if let Err(e) = result {
+            error!("Error batch looking up keys: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                ApiResponse::err("storage_error", "could not query keys"),
+            );
+        }
+    }
+
+    let mut results = serde_json::Map::new();
+    for name in names {
+        let entry = match found.remove(&name) {
+            Some((pubkey, generation)) => json!({ "pubkey": pubkey, "generation": generation }),
+            None => json!({ "not_found": true }),
+        };
+        results.insert(name, entry);
+    }
+    (StatusCode::OK, ApiResponse::ok(Value::Object(results)))
+}
+
+/// Load the server's long-term x25519 secret from `path`, generating and
+/// persisting a fresh one on first boot. This key is published at
+/// `GET /server-key`, so it must survive restarts or every client-cached
+/// envelope (and any in-flight `POST /encrypted`) stops decrypting.
+fn load_or_generate_server_secret(path: &str) -> Result<StaticSecret> {
+    match std::fs::read(path) {
+        Ok(bytes) => {
+            let bytes: [u8; 32] = bytes
+                .try_into()
+                .map_err(|_| eyre!("server secret file {path} is not 32 bytes"))?;
+            Ok(StaticSecret::from(bytes))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let secret = StaticSecret::random_from_rng(thread_rng());
+            std::fs::write(path, secret.to_bytes())?;
+            Ok(secret)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
@@ -89,11 +456,15 @@ async fn main() -> Result<()> {
         r#"CREATE TABLE IF NOT EXISTS keys (
     id INTEGER PRIMARY KEY,
     name TEXT UNIQUE NOT NULL,
-    pubkey BLOB
+    pubkey BLOB,
+    generation INTEGER NOT NULL DEFAULT 1,
+    revoked INTEGER NOT NULL DEFAULT 0
 )"#,
         (),
     )?;
     let my_key = Aes256Gcm::generate_key(thread_rng());
+    let server_secret = load_or_generate_server_secret("server_x25519.key")?;
+    let server_public = X25519PublicKey::from(&server_secret);
 
     info!("Starting server...");
 
@@ -101,60 +472,114 @@ async fn main() -> Result<()> {
         .and(warp::path!("challenge"))
         .and(warp::body::json())
         .map(move |request: Request| {
-            let challenge = Challenge::new_challenge(&my_key, &request.pubkey);
-            warp::reply::json(&challenge)
+            warp::reply::json(&ApiResponse::ok(handle_challenge(&my_key, request)))
         });
 
     let post_response = warp::post()
         .and(warp::path!("response"))
         .and(warp::body::json())
-        .map(move |response: Response| match response.verify(&my_key) {
-            Some(pubkey) => {
-                let keybytes = bincode::serialize(&pubkey).unwrap();
-                let res = db.lock().unwrap().execute(
-                    "INSERT INTO keys (name, pubkey) VALUES (?1, ?2);",
-                    params![response.name, keybytes],
-                );
-                match res {
-                    Ok(_) => {
-                        info!("Inserted key for {}", response.name);
-                        warp::reply::with_status(warp::reply::json(&()), StatusCode::CREATED)
-                    }
-                    Err(e) => {
-                        error!("Error inserting key for {}: {}", response.name, e);
-                        if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) {
-                            warp::reply::with_status(
-                                warp::reply::json(&json!({"error": "name taken"})),
-                                StatusCode::CONFLICT,
-                            )
-                        } else {
-                            warp::reply::with_status(
-                                warp::reply::json(&json!({"error": "could not insert"})),
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                            )
-                        }
-                    }
+        .map(move |response: Response| {
+            let (status, body) = handle_response(db, &my_key, &response);
+            warp::reply::with_status(warp::reply::json(&body), status)
+        });
+
+    let post_rotate = warp::post()
+        .and(warp::path!("rotate"))
+        .and(warp::body::json())
+        .map(move |request: RotateRequest| {
+            let (status, body) = handle_rotate(db, &my_key, request);
+            warp::reply::with_status(warp::reply::json(&body), status)
+        });
+
+    let post_revoke = warp::post()
+        .and(warp::path!("revoke"))
+        .and(warp::body::json())
+        .map(move |request: RevokeRequest| {
+            let (status, body) = handle_revoke(db, &my_key, request);
+            warp::reply::with_status(warp::reply::json(&body), status)
+        });
+
+    let post_keys = warp::post()
+        .and(warp::path!("keys"))
+        .and(warp::body::json())
+        .map(move |request: BatchKeyRequest| {
+            let (status, body) = handle_batch_keys(db, request);
+            warp::reply::with_status(warp::reply::json(&body), status)
+        });
+
+    let get_server_key = warp::get().and(warp::path!("server-key")).map(move || {
+        warp::reply::json(&ApiResponse::ok(json!({ "pubkey": server_public.as_bytes() })))
+    });
+
+    let post_encrypted = warp::post()
+        .and(warp::path!("encrypted"))
+        .and(warp::body::json())
+        .map(move |request: EncryptedRequest| -> Box<dyn warp::reply::Reply> {
+            let their_pubkey = X25519PublicKey::from(request.ephemeral_pubkey);
+            let symmetric_key = crypto::get_x25519_symmetric_key(&server_secret, &their_pubkey);
+            let plaintext = match request
+                .iv
+                .as_slice()
+                .try_into()
+                .ok()
+                .and_then(|iv: &AesNonce| crypto::decrypt_aes_gcm(&symmetric_key, iv, &request.ciphertext))
+            {
+                Some(plaintext) => plaintext,
+                None => {
+                    return Box::new(warp::reply::with_status(
+                        warp::reply::json(&ApiResponse::<()>::err(
+                            "decryption_failed",
+                            "could not decrypt envelope",
+                        )),
+                        StatusCode::BAD_REQUEST,
+                    ))
                 }
-            }
-            None => warp::reply::with_status(
-                warp::reply::json(&json!({"error": "failed challenge"})),
-                StatusCode::BAD_REQUEST,
-            ),
+            };
+            let inner: InnerRequest = match serde_json::from_slice(&plaintext) {
+                Ok(inner) => inner,
+                Err(_) => {
+                    return Box::new(warp::reply::with_status(
+                        warp::reply::json(&ApiResponse::<()>::err(
+                            "malformed_request",
+                            "malformed inner request",
+                        )),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                }
+            };
+            let (status, body) = match inner {
+                InnerRequest::Challenge(request) => (
+                    StatusCode::OK,
+                    ApiResponse::ok(json!(handle_challenge(&my_key, request))),
+                ),
+                InnerRequest::Response(response) => handle_response(db, &my_key, &response),
+                InnerRequest::Keys(request) => handle_batch_keys(db, request),
+            };
+            let reply_plaintext =
+                serde_json::to_vec(&json!({ "status": status.as_u16(), "body": body })).unwrap();
+            let (reply_iv, reply_ciphertext) =
+                crypto::encrypt_aes_gcm(&symmetric_key, &reply_plaintext);
+            Box::new(warp::reply::json(&EncryptedReply {
+                iv: reply_iv.to_vec(),
+                ciphertext: reply_ciphertext,
+            }))
         });
 
     let get_key = warp::get().and(warp::path!("key" / String)).map(
         move |name: String| -> Box<dyn warp::reply::Reply> {
             let res = db.lock().unwrap().query_row(
-                "SELECT pubkey FROM keys WHERE name = ?1",
+                "SELECT pubkey, generation FROM keys WHERE name = ?1 AND revoked = 0",
                 params![&name],
-                |row| row.get::<_, Vec<u8>>(0),
+                |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?)),
             );
             match res {
-                Ok(bytes) => Box::new(warp::reply::json(&json!({ "pubkey": bytes }))),
+                Ok((bytes, generation)) => Box::new(warp::reply::json(&ApiResponse::ok(
+                    json!({ "pubkey": bytes, "generation": generation }),
+                ))),
                 Err(err) => {
                     info!("Failed to retrieve {}: {}", name, err);
                     Box::new(warp::reply::with_status(
-                        warp::reply::json(&json!({"error": "not found"})),
+                        warp::reply::json(&ApiResponse::<()>::err("not_found", "no key registered for that name")),
                         StatusCode::NOT_FOUND,
                     ))
                 }
@@ -162,9 +587,41 @@ async fn main() -> Result<()> {
         },
     );
 
-    let routes = post_challenge.or(post_response).or(get_key);
+    let routes = post_challenge
+        .or(post_response)
+        .or(post_rotate)
+        .or(post_revoke)
+        .or(post_keys)
+        .or(get_key)
+        .or(get_server_key)
+        .or(post_encrypted);
+
+    match std::env::var("EMBERKEYD_DOMAIN") {
+        Ok(domain) => {
+            let contact_email = std::env::var("EMBERKEYD_CONTACT_EMAIL")
+                .unwrap_or_else(|_| format!("admin@{domain}"));
+            let config = acme::AcmeConfig::from_env(domain, contact_email);
+            let challenge_store: &'static acme::ChallengeStore =
+                Box::leak(Box::new(Mutex::new(HashMap::new())));
+
+            let routes = routes.or(acme::challenge_route(challenge_store));
+
+            acme::obtain_certificate(&config, challenge_store).await?;
+            acme::spawn_renewal_task(config.clone(), challenge_store);
 
-    warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+            info!("Starting server with public TLS on :443");
+            warp::serve(routes)
+                .tls()
+                .cert_path(&config.cert_path)
+                .key_path(&config.key_path)
+                .run(([0, 0, 0, 0], 443))
+                .await;
+        }
+        Err(_) => {
+            info!("EMBERKEYD_DOMAIN not set, binding plaintext to localhost only");
+            warp::serve(routes).run(([127, 0, 0, 1], 3030)).await;
+        }
+    }
 
     Ok(())
 }